@@ -0,0 +1,58 @@
+//! Error types.
+
+use std::fmt;
+
+/// A specialized [`Result`](std::result::Result) type using [`Error`].
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Errors returned by this crate.
+#[derive(Debug)]
+pub enum Error {
+    /// Failed to initialize the JS engine.
+    JsInitError(String),
+    /// Failed to execute JS code, carrying the exception thrown by the
+    /// engine, or only the engine's error message when nothing was thrown.
+    JsExecError(JsException),
+    /// Failed to convert a JS value.
+    JsValueError(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::JsInitError(message) => write!(f, "failed to initialize JS engine: {message}"),
+            Self::JsExecError(exception) => write!(f, "failed to execute JS code: {exception}"),
+            Self::JsValueError(message) => write!(f, "failed to convert JS value: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// A structured JS exception captured from a thrown value.
+///
+/// KaTeX's `ParseError` carries a `position` pointing at the offending
+/// token, letting callers distinguish a genuine LaTeX parse error (which
+/// they may want to render inline) from an engine or runtime failure.
+#[derive(Debug, Clone, Default)]
+pub struct JsException {
+    /// The exception's `name` property (e.g. `"ParseError"`), if the thrown
+    /// value was an object with one.
+    pub name: Option<String>,
+    /// The exception's `message` property, or its stringified form when the
+    /// thrown value was not an object.
+    pub message: String,
+    /// The exception's `stack` property, if the engine populated one.
+    pub stack: Option<String>,
+    /// The exception's `position` property, as set by KaTeX's `ParseError`.
+    pub position: Option<i32>,
+}
+
+impl fmt::Display for JsException {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "{name}: {}", self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
@@ -0,0 +1,147 @@
+//! Abstraction over JS engines used to run the bundled KaTeX script.
+
+pub mod rquickjs;
+
+use crate::error::Result;
+
+/// A JS execution engine capable of running the KaTeX bundle.
+pub trait JsEngine: Sized {
+    /// The value type produced by this engine.
+    type JsValue<'a>: JsValue<'a>
+    where
+        Self: 'a;
+
+    /// Creates a new engine.
+    fn new() -> Result<Self>;
+
+    /// Evaluates `code` and returns the resulting value.
+    fn eval<'a>(&'a self, code: &str) -> Result<Self::JsValue<'a>>;
+
+    /// Compiles `code` to bytecode without executing it.
+    ///
+    /// The result can be persisted with [`CompiledScript::to_bytes`] and
+    /// later re-instantiated with [`CompiledScript::from_bytes`], skipping
+    /// the parsing cost of [`JsEngine::eval`] on subsequent runs.
+    ///
+    /// Backends may need to compile `code` as a module rather than a script
+    /// to obtain bytecode ahead of execution; see [`JsEngine::eval_compiled`]
+    /// for how that affects the returned value.
+    fn compile(&self, code: &str) -> Result<CompiledScript>;
+
+    /// Evaluates a script previously produced by [`JsEngine::compile`].
+    ///
+    /// Unlike [`JsEngine::eval`], whose result is `code`'s script-mode
+    /// completion value, the value returned here may instead be `code`'s
+    /// `export default` value (or `undefined` if it has none), since some
+    /// backends can only precompile bytecode by treating `code` as an ES
+    /// module. Use this for `code` that runs for its side effects (e.g.
+    /// defining globals) rather than for its return value; use
+    /// [`JsEngine::eval`] when the completion value matters.
+    fn eval_compiled<'a>(&'a self, script: &CompiledScript) -> Result<Self::JsValue<'a>>;
+
+    /// Calls a global function named `func_name` with `args`.
+    fn call_function<'a>(
+        &'a self,
+        func_name: &str,
+        args: impl Iterator<Item = Self::JsValue<'a>>,
+    ) -> Result<Self::JsValue<'a>>;
+
+    /// Creates a boolean value.
+    fn create_bool_value(&self, input: bool) -> Result<Self::JsValue<'_>>;
+
+    /// Creates an integer value.
+    fn create_int_value(&self, input: i32) -> Result<Self::JsValue<'_>>;
+
+    /// Creates a floating-point value.
+    fn create_float_value(&self, input: f64) -> Result<Self::JsValue<'_>>;
+
+    /// Creates a string value.
+    fn create_string_value(&self, input: String) -> Result<Self::JsValue<'_>>;
+
+    /// Creates an object value from key/value pairs.
+    fn create_object_value<'a>(
+        &'a self,
+        input: impl Iterator<Item = (String, Self::JsValue<'a>)>,
+    ) -> Result<Self::JsValue<'a>>;
+
+    /// Installs a global JS function named `name` backed by the Rust closure `f`.
+    ///
+    /// When called from script, the arguments are marshalled into
+    /// [`Self::JsValue`] and passed to `f`; the value `f` returns becomes the
+    /// call's result. This lets callers expose macro resolvers, sanitizers,
+    /// or trust handlers to KaTeX without patching the bundled JS.
+    fn register_function<F>(&self, name: &str, f: F) -> Result<()>
+    where
+        F: for<'a> Fn(Vec<Self::JsValue<'a>>) -> Result<Self::JsValue<'a>> + 'static;
+
+    /// Installs a sink for `console.log`/`info`/`warn`/`error` calls made by
+    /// the running script.
+    ///
+    /// KaTeX uses `console.warn` to report unsupported commands and
+    /// strict-mode violations; without a sink these are silently dropped.
+    fn set_console<F>(&self, handler: F) -> Result<()>
+    where
+        F: Fn(ConsoleLevel, String) + 'static;
+}
+
+/// Severity level of a `console` call forwarded to a [`JsEngine::set_console`]
+/// handler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleLevel {
+    /// `console.log`.
+    Log,
+    /// `console.info`.
+    Info,
+    /// `console.warn`.
+    Warn,
+    /// `console.error`.
+    Error,
+}
+
+/// A value produced by a [`JsEngine`].
+pub trait JsValue<'a>: std::fmt::Debug {
+    /// Converts the value into a string.
+    fn into_string(self) -> Result<String>;
+
+    /// Converts the value into a boolean.
+    fn into_bool(self) -> Result<bool>;
+
+    /// Converts the value into a 64-bit float.
+    fn into_f64(self) -> Result<f64>;
+
+    /// Converts the value into a 32-bit integer.
+    fn into_i32(self) -> Result<i32>;
+
+    /// Converts the value into a vector, if it is an array.
+    fn into_vec(self) -> Result<Vec<Self>>
+    where
+        Self: Sized;
+
+    /// Converts the value into its own enumerable key/value pairs, if it is
+    /// an object.
+    fn into_entries(self) -> Result<Vec<(String, Self)>>
+    where
+        Self: Sized;
+}
+
+/// Serialized bytecode produced by [`JsEngine::compile`].
+///
+/// This is opaque, engine-specific bytecode; it is only meaningful when fed
+/// back to [`JsEngine::eval_compiled`] on the same engine backend.
+#[derive(Debug, Clone)]
+pub struct CompiledScript {
+    bytes: Vec<u8>,
+}
+
+impl CompiledScript {
+    /// Returns the serialized bytecode.
+    pub fn to_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Rebuilds a [`CompiledScript`] from bytecode previously returned by
+    /// [`CompiledScript::to_bytes`].
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        Self { bytes }
+    }
+}
@@ -1,12 +1,12 @@
 //! JS Engine implemented by [rquickjs](https://crates.io/crates/rquickjs).
 
-use std::collections::HashMap;
+use std::{collections::HashMap, rc::Rc};
 
 use rquickjs::IntoJs;
 
 use crate::{
-    error::{Error, Result},
-    js_engine::{JsEngine, JsValue},
+    error::{Error, JsException, Result},
+    js_engine::{CompiledScript, ConsoleLevel, JsEngine, JsValue},
 };
 
 /// rquickjs Engine.
@@ -14,6 +14,31 @@ pub struct Engine {
     context: rquickjs::Context,
 }
 
+impl Engine {
+    /// Stores `value` as host data, replacing any previously stored value.
+    ///
+    /// Backed by the rquickjs `Runtime`'s userdata slot rather than a field
+    /// on `Engine`, so it is reachable from inside registered
+    /// [`JsEngine::register_function`] closures via [`Value::host_data`]:
+    /// those closures only ever capture a `rquickjs::Context` handle (see
+    /// [`JsEngine::register_function`]), never `Engine` itself, so a side
+    /// field on `Engine` could not be read from inside them without
+    /// separately capturing an owned `Engine` handle -- which would create a
+    /// reference cycle, since the registered closure is itself owned by the
+    /// `Engine`'s JS context.
+    pub fn set_host_data<T: 'static>(&self, value: T) {
+        let runtime = self.context.runtime();
+        let _ = runtime.remove_userdata::<T>();
+        let _ = runtime.store_userdata(value);
+    }
+
+    /// Returns the host data previously stored with [`Engine::set_host_data`],
+    /// or `None` if none was stored or it has a different type.
+    pub fn host_data<T: 'static>(&self) -> Option<Rc<T>> {
+        host_data(&self.context)
+    }
+}
+
 impl JsEngine for Engine {
     type JsValue<'a> = Value<'a>;
 
@@ -31,7 +56,44 @@ impl JsEngine for Engine {
                 context: &self.context,
                 value: ctx
                     .eval(code)
-                    .map_err(|e| Error::JsExecError(error_to_string(&ctx, e)))?,
+                    .map_err(|e| exec_error(&ctx, e))?,
+            })
+        })
+    }
+
+    fn compile(&self, code: &str) -> Result<CompiledScript> {
+        self.context.with(|ctx| {
+            let bytes = rquickjs::Module::declare(ctx.clone(), "katex_bundle", code)
+                .map_err(|e| exec_error(&ctx, e))?
+                .write(false)
+                .map_err(|e| exec_error(&ctx, e))?;
+            Ok(CompiledScript::from_bytes(bytes))
+        })
+    }
+
+    fn eval_compiled<'a>(&'a self, script: &CompiledScript) -> Result<Self::JsValue<'a>> {
+        self.context.with(|ctx| {
+            let module = unsafe { rquickjs::Module::load(ctx.clone(), script.to_bytes()) }
+                .map_err(|e| exec_error(&ctx, e))?;
+            let (module, promise) = module
+                .eval()
+                .map_err(|e| exec_error(&ctx, e))?;
+            promise
+                .finish::<()>()
+                .map_err(|e| exec_error(&ctx, e))?;
+            // `compile` has to declare `code` as a module to get bytecode
+            // out of it ahead of time, so there is no script-mode completion
+            // value to hand back here the way `eval` does. The closest
+            // analogue a module exposes is its `default` export; fall back
+            // to `undefined` when `code` does not set one.
+            let default = module
+                .namespace()
+                .map_err(|e| exec_error(&ctx, e))?
+                .get::<_, rquickjs::Value>("default")
+                .unwrap_or_else(|_| rquickjs::Value::new_undefined(ctx.clone()));
+            Ok(Value {
+                context: &self.context,
+                value: rquickjs::Persistent::save(&ctx, default),
             })
         })
     }
@@ -52,7 +114,7 @@ impl JsEngine for Engine {
                         js_args.push_args(args.map(|v| v.value))?;
                         function.call_arg(js_args)
                     })
-                    .map_err(|e| Error::JsExecError(error_to_string(&ctx, e)))?,
+                    .map_err(|e| exec_error(&ctx, e))?,
             })
         })
     }
@@ -65,7 +127,7 @@ impl JsEngine for Engine {
                     &ctx,
                     input
                         .into_js(&ctx)
-                        .map_err(|e| Error::JsExecError(error_to_string(&ctx, e)))?,
+                        .map_err(|e| exec_error(&ctx, e))?,
                 ),
             })
         })
@@ -79,7 +141,7 @@ impl JsEngine for Engine {
                     &ctx,
                     input
                         .into_js(&ctx)
-                        .map_err(|e| Error::JsExecError(error_to_string(&ctx, e)))?,
+                        .map_err(|e| exec_error(&ctx, e))?,
                 ),
             })
         })
@@ -93,7 +155,7 @@ impl JsEngine for Engine {
                     &ctx,
                     input
                         .into_js(&ctx)
-                        .map_err(|e| Error::JsExecError(error_to_string(&ctx, e)))?,
+                        .map_err(|e| exec_error(&ctx, e))?,
                 ),
             })
         })
@@ -107,7 +169,7 @@ impl JsEngine for Engine {
                     &ctx,
                     input
                         .into_js(&ctx)
-                        .map_err(|e| Error::JsExecError(error_to_string(&ctx, e)))?,
+                        .map_err(|e| exec_error(&ctx, e))?,
                 ),
             })
         })
@@ -126,11 +188,104 @@ impl JsEngine for Engine {
                         .map(|(k, v)| (k, v.value))
                         .collect::<HashMap<_, _>>()
                         .into_js(&ctx)
-                        .map_err(|e| Error::JsExecError(error_to_string(&ctx, e)))?,
+                        .map_err(|e| exec_error(&ctx, e))?,
                 ),
             })
         })
     }
+
+    fn register_function<F>(&self, name: &str, f: F) -> Result<()>
+    where
+        F: for<'a> Fn(Vec<Self::JsValue<'a>>) -> Result<Self::JsValue<'a>> + 'static,
+    {
+        self.context.with(|ctx| {
+            // The JS-owned closure below needs its own `rquickjs::Context` to
+            // construct `Value`s from. Moving an owned clone in (closures
+            // already own their captures) keeps only one more handle to the
+            // same underlying JS context alive for as long as the registered
+            // function itself is, instead of leaking a handle for the rest
+            // of the process the way borrowing `self.context` via a
+            // `'static` workaround would.
+            let context = self.context.clone();
+            ctx.globals()
+                .set(
+                    name,
+                    rquickjs::Function::new(
+                        ctx.clone(),
+                        move |ctx: rquickjs::Ctx, args: rquickjs::function::Rest<rquickjs::Value>| {
+                            let values = args
+                                .into_inner()
+                                .into_iter()
+                                .map(|value| Value {
+                                    context: &context,
+                                    value: rquickjs::Persistent::save(&ctx, value),
+                                })
+                                .collect::<Vec<_>>();
+                            match f(values) {
+                                Ok(result) => result.value.restore(&ctx),
+                                Err(e) => Err(ctx.throw(
+                                    rquickjs::String::from_str(ctx.clone(), &format!("{e}"))?.into_value(),
+                                )),
+                            }
+                        },
+                    )
+                    .map_err(|e| exec_error(&ctx, e))?,
+                )
+                .map_err(|e| exec_error(&ctx, e))
+        })
+    }
+
+    fn set_console<F>(&self, handler: F) -> Result<()>
+    where
+        F: Fn(ConsoleLevel, String) + 'static,
+    {
+        self.context.with(|ctx| {
+            let console =
+                rquickjs::Object::new(ctx.clone()).map_err(|e| exec_error(&ctx, e))?;
+            let handler = std::rc::Rc::new(handler);
+            for (method, level) in [
+                ("log", ConsoleLevel::Log),
+                ("info", ConsoleLevel::Info),
+                ("warn", ConsoleLevel::Warn),
+                ("error", ConsoleLevel::Error),
+            ] {
+                let handler = handler.clone();
+                console
+                    .set(
+                        method,
+                        rquickjs::Function::new(
+                            ctx.clone(),
+                            move |ctx: rquickjs::Ctx, args: rquickjs::function::Rest<rquickjs::Value>| {
+                                let message = args
+                                    .into_inner()
+                                    .iter()
+                                    .map(|value| stringify_console_arg(&ctx, value))
+                                    .collect::<Vec<_>>()
+                                    .join(" ");
+                                handler(level, message);
+                            },
+                        )
+                        .map_err(|e| exec_error(&ctx, e))?,
+                    )
+                    .map_err(|e| exec_error(&ctx, e))?;
+            }
+            ctx.globals()
+                .set("console", console)
+                .map_err(|e| exec_error(&ctx, e))
+        })
+    }
+}
+
+/// Stringifies a single `console.*` argument for concatenation into a log
+/// message, using JS's own `String()` conversion (numbers as numeric text,
+/// `true`/`false`, `null`/`undefined`, objects via `toString`) rather than
+/// Rust's debug representation. Falls back to the debug representation only
+/// when that conversion itself fails.
+fn stringify_console_arg(ctx: &rquickjs::Ctx, value: &rquickjs::Value) -> String {
+    ctx.globals()
+        .get::<_, rquickjs::Function>("String")
+        .and_then(|string_fn| string_fn.call::<_, String>((value.clone(),)))
+        .unwrap_or_else(|_| format!("{value:?}"))
 }
 
 /// rquickjs Value.
@@ -145,23 +300,295 @@ impl<'a> std::fmt::Debug for Value<'a> {
     }
 }
 
+impl<'a> Value<'a> {
+    /// Returns the host data previously stored with
+    /// [`Engine::set_host_data`] on the engine that produced this value.
+    ///
+    /// This is how a [`JsEngine::register_function`] closure reaches host
+    /// data: it receives its arguments as `Value`s, each carrying a handle
+    /// to the engine's `rquickjs::Context`, so any argument -- not just
+    /// `Engine` itself -- can be asked for it. See [`Engine::host_data`] for
+    /// when this returns `None`.
+    pub fn host_data<T: 'static>(&self) -> Option<Rc<T>> {
+        host_data(self.context)
+    }
+}
+
+/// Returns the host data of type `T` stored in `context`'s `Runtime`
+/// userdata slot by [`Engine::set_host_data`], or `None` if none was stored
+/// or it has a different type. Shared by [`Engine::host_data`] and
+/// [`Value::host_data`], which only differ in how they get to a `Context`.
+fn host_data<T: 'static>(context: &rquickjs::Context) -> Option<Rc<T>> {
+    context.runtime().userdata::<T>()
+}
+
 impl<'a> JsValue<'a> for Value<'a> {
     fn into_string(self) -> Result<String> {
         self.context.with(|ctx| {
             self.value
                 .restore(&ctx)
-                .map_err(|e| Error::JsValueError(error_to_string(&ctx, e)))?
+                .map_err(|e| exec_error(&ctx, e))?
                 .into_string()
                 .ok_or_else(|| Error::JsValueError("cannot convert value to string".to_owned()))?
                 .to_string()
-                .map_err(|e| Error::JsValueError(error_to_string(&ctx, e)))
+                .map_err(|e| exec_error(&ctx, e))
+        })
+    }
+
+    fn into_bool(self) -> Result<bool> {
+        self.context.with(|ctx| {
+            self.value
+                .restore(&ctx)
+                .map_err(|e| exec_error(&ctx, e))?
+                .as_bool()
+                .ok_or_else(|| Error::JsValueError("cannot convert value to bool".to_owned()))
+        })
+    }
+
+    fn into_f64(self) -> Result<f64> {
+        self.context.with(|ctx| {
+            self.value
+                .restore(&ctx)
+                .map_err(|e| exec_error(&ctx, e))?
+                .as_number()
+                .ok_or_else(|| Error::JsValueError("cannot convert value to f64".to_owned()))
+        })
+    }
+
+    fn into_i32(self) -> Result<i32> {
+        self.context.with(|ctx| {
+            self.value
+                .restore(&ctx)
+                .map_err(|e| exec_error(&ctx, e))?
+                .as_int()
+                .ok_or_else(|| Error::JsValueError("cannot convert value to i32".to_owned()))
+        })
+    }
+
+    fn into_vec(self) -> Result<Vec<Self>> {
+        self.context.with(|ctx| {
+            self.value
+                .restore(&ctx)
+                .map_err(|e| exec_error(&ctx, e))?
+                .into_array()
+                .ok_or_else(|| Error::JsValueError("cannot convert value to array".to_owned()))?
+                .iter::<rquickjs::Value>()
+                .map(|item| {
+                    Ok(Value {
+                        context: self.context,
+                        value: rquickjs::Persistent::save(
+                            &ctx,
+                            item.map_err(|e| exec_error(&ctx, e))?,
+                        ),
+                    })
+                })
+                .collect()
+        })
+    }
+
+    fn into_entries(self) -> Result<Vec<(String, Self)>> {
+        self.context.with(|ctx| {
+            let object = self
+                .value
+                .restore(&ctx)
+                .map_err(|e| exec_error(&ctx, e))?
+                .into_object()
+                .ok_or_else(|| Error::JsValueError("cannot convert value to object".to_owned()))?;
+            object
+                .props::<String, rquickjs::Value>()
+                .map(|entry| {
+                    let (key, value) = entry.map_err(|e| exec_error(&ctx, e))?;
+                    Ok((
+                        key,
+                        Value {
+                            context: self.context,
+                            value: rquickjs::Persistent::save(&ctx, value),
+                        },
+                    ))
+                })
+                .collect()
         })
     }
 }
 
-fn error_to_string(ctx: &rquickjs::Ctx, e: rquickjs::Error) -> String {
-    match e {
-        rquickjs::Error::Exception => format!("{e}: {:?}", ctx.catch()),
-        _ => format!("{e}"),
+/// Converts an `rquickjs::Error` into an [`Error::JsExecError`], pulling the
+/// exception's `name`/`message`/`stack`/`position` out of the caught value
+/// when it is an object (as KaTeX's `ParseError` is).
+fn exec_error(ctx: &rquickjs::Ctx, e: rquickjs::Error) -> Error {
+    Error::JsExecError(match e {
+        rquickjs::Error::Exception => to_js_exception(ctx.catch()),
+        _ => JsException {
+            message: format!("{e}"),
+            ..Default::default()
+        },
+    })
+}
+
+fn to_js_exception(value: rquickjs::Value) -> JsException {
+    match value.as_object() {
+        Some(object) => JsException {
+            name: object.get::<_, String>("name").ok(),
+            message: object
+                .get::<_, String>("message")
+                .unwrap_or_else(|_| format!("{value:?}")),
+            stack: object.get::<_, String>("stack").ok(),
+            position: object.get::<_, i32>("position").ok(),
+        },
+        None => JsException {
+            message: stringify_console_arg(value.ctx(), &value),
+            ..Default::default()
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::js_engine::JsEngine;
+
+    #[test]
+    fn register_function_is_callable_from_js() {
+        let engine = Engine::new().unwrap();
+        engine
+            .register_function("identity", |mut args: Vec<Value<'_>>| Ok(args.remove(0)))
+            .unwrap();
+        let result = engine.eval("identity(21 * 2)").unwrap();
+        assert_eq!(result.into_f64().unwrap(), 42.0);
+    }
+
+    #[test]
+    fn eval_compiled_returns_the_default_export() {
+        let engine = Engine::new().unwrap();
+        let script = engine.compile("export default 1 + 1;").unwrap();
+        let result = engine.eval_compiled(&script).unwrap();
+        assert_eq!(result.into_f64().unwrap(), 2.0);
+    }
+
+    // `compile` has to declare `code` as an ES module to get bytecode out of
+    // it ahead of time, and modules are always strict -- unlike `eval`'s
+    // script-mode execution, they reject a bare assignment to an undeclared
+    // name instead of silently creating a global. This is a synthetic
+    // stand-in for that gap, not a test against the real KaTeX bundle (this
+    // tree does not vendor it), but it pins down the one-line repro of the
+    // documented divergence so a regression here is caught.
+    #[test]
+    fn eval_compiled_runs_in_strict_module_mode_unlike_eval() {
+        let engine = Engine::new().unwrap();
+        assert!(engine
+            .eval("implicitGlobalFromSloppyScript = 1; implicitGlobalFromSloppyScript")
+            .is_ok());
+
+        let script = engine
+            .compile("implicitGlobalFromStrictModule = 1;")
+            .unwrap();
+        assert!(engine.eval_compiled(&script).is_err());
+    }
+
+    #[test]
+    fn engine_host_data_round_trips() {
+        let engine = Engine::new().unwrap();
+        assert!(engine.host_data::<i32>().is_none());
+        engine.set_host_data(7i32);
+        assert_eq!(*engine.host_data::<i32>().unwrap(), 7);
+    }
+
+    #[test]
+    fn host_data_is_reachable_from_registered_function_closures() {
+        use std::cell::RefCell;
+
+        let engine = Engine::new().unwrap();
+        engine.set_host_data(42i32);
+        let observed = Rc::new(RefCell::new(None));
+        let observed_in_closure = observed.clone();
+        engine
+            .register_function("readHostData", move |args: Vec<Value<'_>>| {
+                *observed_in_closure.borrow_mut() = args[0].host_data::<i32>().map(|v| *v);
+                Ok(args.into_iter().next().unwrap())
+            })
+            .unwrap();
+        engine.eval("readHostData(0)").unwrap();
+        assert_eq!(*observed.borrow(), Some(42));
+    }
+
+    #[test]
+    fn typed_value_extraction_round_trips() {
+        let engine = Engine::new().unwrap();
+
+        assert!(engine.create_bool_value(true).unwrap().into_bool().unwrap());
+        assert_eq!(engine.create_int_value(42).unwrap().into_i32().unwrap(), 42);
+        assert_eq!(
+            engine.create_float_value(4.2).unwrap().into_f64().unwrap(),
+            4.2
+        );
+        assert_eq!(
+            engine
+                .create_string_value("hello".to_owned())
+                .unwrap()
+                .into_string()
+                .unwrap(),
+            "hello"
+        );
+
+        let array = engine.eval("[1, 2, 3]").unwrap().into_vec().unwrap();
+        let values: Vec<i32> = array.into_iter().map(|v| v.into_i32().unwrap()).collect();
+        assert_eq!(values, [1, 2, 3]);
+
+        let entries = engine.eval("({a: 1, b: 2})").unwrap().into_entries().unwrap();
+        let mut entries: Vec<(String, i32)> = entries
+            .into_iter()
+            .map(|(k, v)| (k, v.into_i32().unwrap()))
+            .collect();
+        entries.sort();
+        assert_eq!(entries, [("a".to_owned(), 1), ("b".to_owned(), 2)]);
+    }
+
+    #[test]
+    fn set_console_stringifies_non_string_args_via_js() {
+        use std::cell::RefCell;
+
+        let engine = Engine::new().unwrap();
+        let messages = Rc::new(RefCell::new(Vec::new()));
+        let messages_in_handler = messages.clone();
+        engine
+            .set_console(move |level, message| messages_in_handler.borrow_mut().push((level, message)))
+            .unwrap();
+        engine
+            .eval("console.warn('bad glyph', 42, true, null, undefined)")
+            .unwrap();
+        assert_eq!(
+            messages.borrow().as_slice(),
+            [(
+                ConsoleLevel::Warn,
+                "bad glyph 42 true null undefined".to_owned()
+            )]
+        );
+    }
+
+    #[test]
+    fn thrown_object_is_decoded_into_a_structured_exception() {
+        let engine = Engine::new().unwrap();
+        let err = engine
+            .eval("throw { name: 'ParseError', message: 'bad token', position: 3 };")
+            .unwrap_err();
+        match err {
+            Error::JsExecError(exception) => {
+                assert_eq!(exception.name.as_deref(), Some("ParseError"));
+                assert_eq!(exception.message, "bad token");
+                assert_eq!(exception.position, Some(3));
+            }
+            other => panic!("expected JsExecError, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn throwing_getter_during_into_entries_surfaces_a_structured_exception() {
+        let engine = Engine::new().unwrap();
+        let value = engine
+            .eval("({ get a() { throw { name: 'Boom', message: 'nope' }; } })")
+            .unwrap();
+        match value.into_entries().unwrap_err() {
+            Error::JsExecError(exception) => assert_eq!(exception.name.as_deref(), Some("Boom")),
+            other => panic!("expected JsExecError, got {other:?}"),
+        }
     }
 }
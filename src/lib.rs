@@ -0,0 +1,4 @@
+//! Rust bindings for [KaTeX](https://katex.org/).
+
+pub mod error;
+pub mod js_engine;